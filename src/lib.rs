@@ -2,12 +2,64 @@
 
 //! A platform agnostic driver to interface with 7-segments displays
 //! connected to shift registers
-//! 
+//!
 //! This is work-in-progress!
 //!
+//! `SegmentDisplay<SPI, PIN, DIGITS>` is generic over the number of digits in
+//! the shift-register chain, so a 4-digit module and an 8-digit module both
+//! use the same driver.
+//!
+//! `SegmentDisplay::writer` returns a `core::fmt::Write` sink, so it can be used as a
+//! `write!` target for formatted output, e.g. `write!(display.writer(), "{:>4}", n)`.
+//! Decimal points are supported via `write_number_fixed`/`set_decimal_point`,
+//! or by including a `.` in a `write!`-formatted string.
+//!
+//! By default this crate builds against the `embedded-hal` 0.2 traits.
+//! Enable the `eh1` feature to build against the stabilized `embedded-hal`
+//! 1.0 traits (`embedded_hal_1::spi::SpiBus`, `embedded_hal_1::digital::OutputPin`,
+//! `embedded_hal_1::delay::DelayNs`, pulled in under the `embedded-hal-1` crate
+//! name since `embedded-hal` 0.2 is still the default dependency) instead. The
+//! two are mutually exclusive. `refresh_if_due` is only available without `eh1`,
+//! since embedded-hal 1.0 dropped the `CountDown`/`nb` timer traits it's built on.
+//!
+//! Enable the `async` feature (which implies `eh1`) for a non-blocking
+//! `refresh`/`refresh_with_delay` built on `embedded-hal-async`, so the display
+//! can be multiplexed from an embassy task instead of busy-blocking the CPU:
+//!
+//! ```ignore
+//! // `ignore`d: this doctest needs the `embassy-executor`/`embassy-time` crates and an
+//! // actual embassy-supported target, neither of which this crate depends on.
+//!#![no_std]
+//!#![no_main]
+//!
+//!use embassy_executor::Spawner;
+//!use embassy_time::{Duration, Timer};
+//!use segment_display::SegmentDisplay;
+//!
+//!#[embassy_executor::task]
+//!async fn display_task<SPI, PIN>(mut segment_display: SegmentDisplay<SPI, PIN, 4>)
+//!where
+//!    SPI: embedded_hal_async::spi::SpiBus<u8> + 'static,
+//!    PIN: embedded_hal_1::digital::OutputPin + 'static,
+//!{
+//!    loop {
+//!        segment_display.refresh().await.unwrap();
+//!        Timer::after(Duration::from_micros(1000)).await;
+//!    }
+//!}
+//!
+//!#[embassy_executor::main]
+//!async fn main(spawner: Spawner) {
+//!    // set up spi and latch, then:
+//!    // spawner.spawn(display_task(segment_display)).unwrap();
+//!}
+//! ```
+//!
 //! Example
 //!
-//! ```no_run
+//! ```ignore
+//! // `ignore`d: this doctest needs the target-specific `nucleo-f401re`/`cortex-m` crates,
+//! // none of which this crate depends on.
 //!
 //!#![no_main]
 //!#![no_std]
@@ -50,7 +102,7 @@
 //!        clocks,
 //!    );
 //!
-//!    let mut segment_display = SegmentDisplay::new(spi, latch);
+//!    let mut segment_display: SegmentDisplay<_, _, 4> = SegmentDisplay::new(spi, latch);
 //!    let mut delay = Delay::new(core.SYST, clocks);
 //!
 //!    segment_display.write_str("HELO");
@@ -63,12 +115,24 @@
 //!
 //! ```
 
+#[cfg(not(feature = "eh1"))]
 use embedded_hal::blocking::delay::DelayUs;
+#[cfg(not(feature = "eh1"))]
 use embedded_hal::blocking::spi;
+#[cfg(not(feature = "eh1"))]
 use embedded_hal::digital::v2::OutputPin;
-
-pub struct SegmentDisplay<SPI, PIN> {
-    back_buffer: [u8; 4],
+#[cfg(not(feature = "eh1"))]
+use embedded_hal::timer::CountDown;
+
+#[cfg(all(feature = "eh1", not(feature = "async")))]
+use embedded_hal_1::delay::DelayNs;
+#[cfg(all(feature = "eh1", not(feature = "async")))]
+use embedded_hal_1::digital::OutputPin;
+#[cfg(all(feature = "eh1", not(feature = "async")))]
+use embedded_hal_1::spi::SpiBus;
+
+pub struct SegmentDisplay<SPI, PIN, const DIGITS: usize> {
+    back_buffer: [u8; DIGITS],
     spi: SPI,
     latch_pin: PIN,
     current_digit: usize,
@@ -82,26 +146,110 @@ pub enum Error<SpiError, PinError> {
 }
 
 
-impl<SPI, PIN> SegmentDisplay<SPI, PIN>
+#[cfg(not(feature = "eh1"))]
+impl<SPI, PIN, const DIGITS: usize> SegmentDisplay<SPI, PIN, DIGITS>
 where
     SPI: spi::Write<u8>,
     PIN: OutputPin,
 {
-    /// Create a new SegmentDisplay
-    pub fn new(spi: SPI, latch_pin: PIN) -> Self {
-        Self {
-            back_buffer: [0xff; 4],
-            spi,
-            latch_pin,
-            current_digit: 0,
-        }
+    /// Refresh the display. Needs to be called periodically with a sufficientlty hight frequenzy
+    /// otherwise the display will flicker.
+    pub fn refresh(&mut self) -> Result<(), Error<SPI::Error, PIN::Error>> {
+        let segments_and_select: [u8; 2] = [
+            // The segments in digit to turn on/off
+            self.back_buffer[self.current_digit],
+            // The current display selector.
+            1 << (DIGITS - 1 - self.current_digit),
+        ];
+
+        self.current_digit = (self.current_digit + 1) % DIGITS;
+
+        self.latch_pin.set_low().map_err(Error::Pin)?;
+
+        self.spi
+                .write(&segments_and_select)
+                .map_err(Error::Spi)?;
+
+        self.latch_pin
+                .set_high()
+                .map_err(Error::Pin)?;
+
+        Ok(())
     }
 
-    /// Release the SegmentDisplay and the resources
-    pub fn release(self) -> (SPI, PIN) {
-        (self.spi, self.latch_pin)
+    pub fn refresh_with_delay<DELAY>(&mut self, delay: &mut DELAY) -> Result<(), Error<SPI::Error, PIN::Error>>
+    where
+        DELAY: DelayUs<u16>,
+    {
+        let segments_and_select: [u8; 2] = [
+            // The segments in digit to turn on/off
+            self.back_buffer[self.current_digit],
+            // The current display selector.
+            1 << (DIGITS - 1 - self.current_digit),
+        ];
+
+        self.current_digit = (self.current_digit + 1) % DIGITS;
+
+        self.latch_pin
+                .set_low()
+                .map_err(Error::Pin)?;
+        self.spi
+                .write(&segments_and_select)
+                .map_err(Error::Spi)?;
+
+        delay.delay_us(100);
+        self.latch_pin
+                .set_high()
+                .map_err(Error::Pin)?;
+
+        Ok(())
+    }
+
+    /// Advance and refresh the display once `timer` has elapsed, without blocking.
+    ///
+    /// Configure `timer` for the per-digit dwell time and call this as often as you like,
+    /// e.g. from a periodic interrupt or a cooperative scheduler: it polls the timer with
+    /// `wait()` and only performs the latch/SPI write when the timer has elapsed, returning
+    /// `nb::Error::WouldBlock` the rest of the time so callers never need to hand-tune a
+    /// delay loop.
+    ///
+    /// Only available without the `eh1`/`async` features: embedded-hal 1.0 dropped the
+    /// `CountDown`/`nb` timer traits this is built on, so there's no equivalent under `eh1`.
+    pub fn refresh_if_due<TIMER>(&mut self, timer: &mut TIMER) -> nb::Result<(), Error<SPI::Error, PIN::Error>>
+    where
+        TIMER: CountDown,
+    {
+        timer.wait().map_err(|_| nb::Error::WouldBlock)?;
+
+        let segments_and_select: [u8; 2] = [
+            // The segments in digit to turn on/off
+            self.back_buffer[self.current_digit],
+            // The current display selector.
+            1 << (DIGITS - 1 - self.current_digit),
+        ];
+
+        self.current_digit = (self.current_digit + 1) % DIGITS;
+
+        self.latch_pin.set_low().map_err(Error::Pin)?;
+
+        self.spi
+                .write(&segments_and_select)
+                .map_err(Error::Spi)?;
+
+        self.latch_pin
+                .set_high()
+                .map_err(Error::Pin)?;
+
+        Ok(())
     }
+}
 
+#[cfg(all(feature = "eh1", not(feature = "async")))]
+impl<SPI, PIN, const DIGITS: usize> SegmentDisplay<SPI, PIN, DIGITS>
+where
+    SPI: SpiBus<u8>,
+    PIN: OutputPin,
+{
     /// Refresh the display. Needs to be called periodically with a sufficientlty hight frequenzy
     /// otherwise the display will flicker.
     pub fn refresh(&mut self) -> Result<(), Error<SPI::Error, PIN::Error>> {
@@ -109,14 +257,14 @@ where
             // The segments in digit to turn on/off
             self.back_buffer[self.current_digit],
             // The current display selector.
-            1 << (4 - 1 - self.current_digit),
+            1 << (DIGITS - 1 - self.current_digit),
         ];
 
-        self.current_digit = (self.current_digit + 1) & 0b11;
+        self.current_digit = (self.current_digit + 1) % DIGITS;
 
         self.latch_pin.set_low().map_err(Error::Pin)?;
 
-        let res = self.spi
+        self.spi
                 .write(&segments_and_select)
                 .map_err(Error::Spi)?;
 
@@ -124,26 +272,26 @@ where
                 .set_high()
                 .map_err(Error::Pin)?;
 
-        Ok(res)
+        Ok(())
     }
 
     pub fn refresh_with_delay<DELAY>(&mut self, delay: &mut DELAY) -> Result<(), Error<SPI::Error, PIN::Error>>
     where
-        DELAY: DelayUs<u16>,
+        DELAY: DelayNs,
     {
         let segments_and_select: [u8; 2] = [
             // The segments in digit to turn on/off
             self.back_buffer[self.current_digit],
             // The current display selector.
-            1 << (4 - 1 - self.current_digit),
+            1 << (DIGITS - 1 - self.current_digit),
         ];
 
-        self.current_digit = (self.current_digit + 1) & 0b11;
+        self.current_digit = (self.current_digit + 1) % DIGITS;
 
         self.latch_pin
                 .set_low()
                 .map_err(Error::Pin)?;
-        let res = self.spi
+        self.spi
                 .write(&segments_and_select)
                 .map_err(Error::Spi)?;
 
@@ -152,11 +300,91 @@ where
                 .set_high()
                 .map_err(Error::Pin)?;
 
-        Ok(res)
+        Ok(())
+    }
+}
+
+#[cfg(feature = "async")]
+impl<SPI, PIN, const DIGITS: usize> SegmentDisplay<SPI, PIN, DIGITS>
+where
+    SPI: embedded_hal_async::spi::SpiBus<u8>,
+    PIN: embedded_hal_1::digital::OutputPin,
+{
+    /// Refresh the display. Needs to be called periodically with a sufficientlty hight frequenzy
+    /// otherwise the display will flicker. `await`s the SPI transfer, so other tasks can run
+    /// while it completes instead of busy-blocking the CPU.
+    pub async fn refresh(&mut self) -> Result<(), Error<SPI::Error, PIN::Error>> {
+        let segments_and_select: [u8; 2] = [
+            // The segments in digit to turn on/off
+            self.back_buffer[self.current_digit],
+            // The current display selector.
+            1 << (DIGITS - 1 - self.current_digit),
+        ];
+
+        self.current_digit = (self.current_digit + 1) % DIGITS;
+
+        self.latch_pin.set_low().map_err(Error::Pin)?;
+
+        self.spi
+                .write(&segments_and_select)
+                .await
+                .map_err(Error::Spi)?;
+
+        self.latch_pin
+                .set_high()
+                .map_err(Error::Pin)?;
+
+        Ok(())
+    }
+
+    pub async fn refresh_with_delay<DELAY>(&mut self, delay: &mut DELAY) -> Result<(), Error<SPI::Error, PIN::Error>>
+    where
+        DELAY: embedded_hal_async::delay::DelayNs,
+    {
+        let segments_and_select: [u8; 2] = [
+            // The segments in digit to turn on/off
+            self.back_buffer[self.current_digit],
+            // The current display selector.
+            1 << (DIGITS - 1 - self.current_digit),
+        ];
+
+        self.current_digit = (self.current_digit + 1) % DIGITS;
+
+        self.latch_pin
+                .set_low()
+                .map_err(Error::Pin)?;
+        self.spi
+                .write(&segments_and_select)
+                .await
+                .map_err(Error::Spi)?;
+
+        delay.delay_us(100).await;
+        self.latch_pin
+                .set_high()
+                .map_err(Error::Pin)?;
+
+        Ok(())
+    }
+}
+
+impl<SPI, PIN, const DIGITS: usize> SegmentDisplay<SPI, PIN, DIGITS> {
+    /// Create a new SegmentDisplay
+    pub fn new(spi: SPI, latch_pin: PIN) -> Self {
+        Self {
+            back_buffer: [0xff; DIGITS],
+            spi,
+            latch_pin,
+            current_digit: 0,
+        }
+    }
+
+    /// Release the SegmentDisplay and the resources
+    pub fn release(self) -> (SPI, PIN) {
+        (self.spi, self.latch_pin)
     }
 
     /// Write characters to the display
-    pub fn write_chars(&mut self, buf: [char; 4]) {
+    pub fn write_chars(&mut self, buf: [char; DIGITS]) {
         for (i, c) in buf.iter().enumerate() {
             self.back_buffer[i] = Self::char_to_segment_code(*c);
         }
@@ -167,41 +395,54 @@ where
 
         self.back_buffer.iter_mut().for_each(|b| *b = !0);
 
-        for (i, c) in s.chars().take(4).enumerate() {
+        for (i, c) in s.chars().take(DIGITS).enumerate() {
             self.back_buffer[i] = Self::char_to_segment_code(c);
         }
     }
 
-    /// Write a number to the display
+    /// Write a number to the display, clamped to what fits in `DIGITS` digits
     pub fn write_number(&mut self, num: usize) {
-        let mut num = num;
+        let max = 10usize.saturating_pow(DIGITS as u32).saturating_sub(1);
+        let mut num = num.min(max);
 
-        if num > 9999 {
-            num = 9999;
+        for i in (0..DIGITS).rev() {
+            self.back_buffer[i] = NUMERALS[num % 10];
+            num /= 10;
         }
+    }
 
-        for (i, div) in [1000, 100, 10].iter().enumerate() {
-            let digit;
-            if num >= i {
-                digit = num / div;
-                num -= div * digit;
-            } else {
-                digit = 0;
-            }
-            self.back_buffer[i] = NUMERALS[digit];
+    /// Write `value` as a fixed-point number with `decimals` digits after the decimal point,
+    /// e.g. on a 4-digit display `write_number_fixed(125, 1)` shows `012.5`, since
+    /// `write_number` pads with leading zeros
+    pub fn write_number_fixed(&mut self, value: usize, decimals: usize) {
+        self.write_number(value);
+
+        if decimals > 0 && decimals < DIGITS {
+            self.set_decimal_point(DIGITS - 1 - decimals, true);
         }
+    }
 
-        self.back_buffer[3] = NUMERALS[num];
+    /// Turn the decimal point segment of `digit` on or off. Out-of-range `digit`s are a no-op.
+    pub fn set_decimal_point(&mut self, digit: usize, on: bool) {
+        let Some(back_buffer) = self.back_buffer.get_mut(digit) else {
+            return;
+        };
+
+        if on {
+            *back_buffer &= !0b1000_0000;
+        } else {
+            *back_buffer |= 0b1000_0000;
+        }
     }
 
     fn char_to_segment_code(c: char) -> u8 {
         if c.is_ascii_digit() {
             let cb = c as u8;
-            let idx = cb - ('0' as u8);
+            let idx = cb - b'0';
             NUMERALS[idx as usize]
         } else if c.is_ascii_alphabetic() {
             let cb = (c as u8) & !0x20; // Convert to uppercase
-            let idx = cb - ('A' as u8);
+            let idx = cb - b'A';
             LETTERS[idx as usize]
         } else {
             // Symbols
@@ -215,6 +456,54 @@ where
     }
 }
 
+impl<SPI, PIN, const DIGITS: usize> SegmentDisplay<SPI, PIN, DIGITS> {
+    /// Returns a `core::fmt::Write` sink over this display, e.g.
+    /// `write!(display.writer(), "{:>4}", n)`.
+    ///
+    /// `core::fmt`'s formatting machinery can call `write_str` several times for a single
+    /// `write!` (once per padding run and once per literal/argument fragment), so the
+    /// returned `Writer` blanks the display once, up front, and accumulates a digit cursor
+    /// across every call made through it instead of each call starting over.
+    pub fn writer(&mut self) -> Writer<'_, SPI, PIN, DIGITS> {
+        self.back_buffer.iter_mut().for_each(|b| *b = !0);
+        Writer {
+            display: self,
+            digit: 0,
+        }
+    }
+}
+
+/// A `core::fmt::Write` sink over a [`SegmentDisplay`], created with [`SegmentDisplay::writer`].
+pub struct Writer<'a, SPI, PIN, const DIGITS: usize> {
+    display: &'a mut SegmentDisplay<SPI, PIN, DIGITS>,
+    digit: usize,
+}
+
+impl<SPI, PIN, const DIGITS: usize> core::fmt::Write for Writer<'_, SPI, PIN, DIGITS> {
+    /// A `.` is mapped onto the decimal point of the preceding digit instead of consuming a
+    /// digit position of its own; every other char is routed through the same character
+    /// table as `write_str`.
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        for c in s.chars() {
+            if c == '.' {
+                if self.digit > 0 {
+                    self.display.set_decimal_point(self.digit - 1, true);
+                }
+                continue;
+            }
+
+            if self.digit >= DIGITS {
+                break;
+            }
+
+            self.display.back_buffer[self.digit] = SegmentDisplay::<SPI, PIN, DIGITS>::char_to_segment_code(c);
+            self.digit += 1;
+        }
+
+        Ok(())
+    }
+}
+
 //           A
 //          ===
 //      F ||   || B